@@ -1,17 +1,50 @@
-use crate::AxVmDeviceConfig;
+use crate::{AxVmDeviceConfig, DeviceError, DeviceResult};
 
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
 use axaddrspace::GuestPhysAddr;
-use axdevice_base::BaseDeviceOps;
+use axdevice_base::{BaseDeviceOps, EmuDeviceType};
 use axerrno::AxResult;
 use axvmconfig::EmulatedDeviceConfig;
+use memory_addr::AddrRange;
+
+/// A snapshot of one registered emulated device
+#[derive(Debug, Clone)]
+pub struct DeviceDescriptor {
+    /// The emulated device type
+    pub emu_type: EmuDeviceType,
+    /// The guest physical address range served by the device
+    pub address_range: AddrRange<GuestPhysAddr>,
+}
+
+/// How MMIO accesses that hit no emulated device are handled
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UnmappedPolicy {
+    /// Fail the access with an error
+    #[default]
+    Error,
+    /// Reads return zero, writes are ignored
+    ReadZeroWriteIgnore,
+    /// Reads return all ones for the access width, writes are ignored
+    ReadOnesWriteIgnore,
+}
+
+/// Return the all-ones value of an access `width` in bytes
+fn width_mask(width: usize) -> DeviceResult<usize> {
+    if width.is_power_of_two() && width <= size_of::<usize>() {
+        Ok(usize::MAX >> (usize::BITS as usize - width * 8))
+    } else {
+        Err(DeviceError::InvalidAccessWidth(width))
+    }
+}
 
 /// represent A vm own devices
 pub struct AxVmDevices {
     /// emu devices
     emu_devices: Vec<Arc<dyn BaseDeviceOps>>,
+    /// policy for accesses that hit no emu device
+    unmapped_policy: UnmappedPolicy,
     // TODO passthrough devices or other type devices ...
 }
 
@@ -21,6 +54,7 @@ impl AxVmDevices {
     pub fn new(config: AxVmDeviceConfig) -> Self {
         let mut this = Self {
             emu_devices: Vec::new(),
+            unmapped_policy: UnmappedPolicy::default(),
         };
 
         Self::init(&mut this, &config.emu_configs);
@@ -53,6 +87,72 @@ impl AxVmDevices {
         */
     }
 
+    /// Set how MMIO accesses that hit no emulated device are handled
+    pub fn set_unmapped_policy(&mut self, policy: UnmappedPolicy) {
+        self.unmapped_policy = policy;
+    }
+
+    /// Add an emulated device, failing if its address range overlaps with an
+    /// already added device
+    pub fn add_mmio_dev(&mut self, dev: Arc<dyn BaseDeviceOps>) -> DeviceResult {
+        self.insert_mmio_devs(core::slice::from_ref(&dev), 1)
+    }
+
+    /// Add a batch of emulated devices, either all of them or none.
+    ///
+    /// Every address range is checked against the already added devices and
+    /// against the rest of the batch before anything is inserted, so an
+    /// overlap or an allocation failure leaves the device set unchanged.
+    pub fn add_mmio_devs(&mut self, devs: Vec<Arc<dyn BaseDeviceOps>>) -> DeviceResult {
+        self.insert_mmio_devs(&devs, devs.len())
+    }
+
+    /// Validate `devs`, reserve room for `reserve` more emu devices and insert
+    /// `devs`, without allocating before the reservation
+    fn insert_mmio_devs(
+        &mut self,
+        devs: &[Arc<dyn BaseDeviceOps>],
+        reserve: usize,
+    ) -> DeviceResult {
+        debug_assert!(reserve >= devs.len());
+        for (i, dev) in devs.iter().enumerate() {
+            let range = dev.address_range();
+            let overlaps = |other: &Arc<dyn BaseDeviceOps>| other.address_range().overlaps(range);
+            if self.emu_devices.iter().any(overlaps) {
+                warn!("emu: {:?} overlaps with an already added device", range);
+                return Err(DeviceError::AddressOverlap(range));
+            }
+            if devs[..i].iter().any(overlaps) {
+                warn!("emu: {:?} overlaps with another device in the batch", range);
+                return Err(DeviceError::AddressOverlap(range));
+            }
+        }
+        // After the reservation succeeds, `extend_from_slice` cannot reallocate.
+        self.reserve_emu_devices(reserve)?;
+        self.emu_devices.extend_from_slice(devs);
+        Ok(())
+    }
+
+    /// Reserve room for `additional` more emu devices, reporting an allocation
+    /// failure instead of aborting
+    fn reserve_emu_devices(&mut self, additional: usize) -> DeviceResult {
+        self.emu_devices
+            .try_reserve(additional)
+            .map_err(|_| DeviceError::NoMemory)
+    }
+
+    /// Snapshot the type and address range of every emulated device,
+    /// e.g. for building the guest device tree or memory map
+    pub fn iter_devices(&self) -> Vec<DeviceDescriptor> {
+        self.emu_devices
+            .iter()
+            .map(|dev| DeviceDescriptor {
+                emu_type: dev.emu_type(),
+                address_range: dev.address_range(),
+            })
+            .collect()
+    }
+
     /// Find specific device by ipa
     pub fn find_dev(&self, ipa: GuestPhysAddr) -> Option<Arc<dyn BaseDeviceOps>> {
         self.emu_devices
@@ -61,21 +161,111 @@ impl AxVmDevices {
             .cloned()
     }
 
+    /// Find all devices whose address range intersects `[start, end)`
+    ///
+    /// An empty (`start == end`) or reversed (`start > end`) span matches nothing.
+    pub fn find_devs_in_range(
+        &self,
+        start: GuestPhysAddr,
+        end: GuestPhysAddr,
+    ) -> Vec<Arc<dyn BaseDeviceOps>> {
+        let Some(range) = AddrRange::try_new(start, end).filter(|r| !r.is_empty()) else {
+            return Vec::new();
+        };
+        self.emu_devices
+            .iter()
+            .filter(|dev| dev.address_range().overlaps(range))
+            .cloned()
+            .collect()
+    }
+
+    /// Find all devices of the given emulated device type
+    pub fn find_by_type(&self, ty: EmuDeviceType) -> Vec<Arc<dyn BaseDeviceOps>> {
+        self.emu_devices
+            .iter()
+            .filter(|dev| dev.emu_type() == ty)
+            .cloned()
+            .collect()
+    }
+
+    /// Find the first device of the given emulated device type
+    pub fn get_first_by_type(&self, ty: EmuDeviceType) -> Option<Arc<dyn BaseDeviceOps>> {
+        self.emu_devices
+            .iter()
+            .find(|dev| dev.emu_type() == ty)
+            .cloned()
+    }
+
     /// Handle the MMIO read by GuestPhysAddr and data width, return the value of the guest want to read
+    ///
+    /// # Errors
+    ///
+    /// Under the default [`UnmappedPolicy::Error`], an address that no emu
+    /// device handles returns [`AxError::BadAddress`] instead of panicking as
+    /// it used to. The ignore policies return [`AxError::InvalidInput`] for an
+    /// invalid access width, and errors reported by the device are passed
+    /// through.
+    ///
+    /// [`AxError::BadAddress`]: axerrno::AxError::BadAddress
+    /// [`AxError::InvalidInput`]: axerrno::AxError::InvalidInput
     pub fn handle_mmio_read(&self, addr: GuestPhysAddr, width: usize) -> AxResult<usize> {
+        self.handle_mmio_read_detailed(addr, width)
+            .map_err(Into::into)
+    }
+
+    /// Same as [`Self::handle_mmio_read`], but return a [`DeviceError`] telling why the read failed
+    pub fn handle_mmio_read_detailed(
+        &self,
+        addr: GuestPhysAddr,
+        width: usize,
+    ) -> DeviceResult<usize> {
         if let Some(emu_dev) = self.find_dev(addr) {
             info!(
                 "emu: {:?} handler read ipa {:#x}",
                 emu_dev.address_range(),
                 addr
             );
-            return emu_dev.handle_read(addr, width);
+            return Ok(emu_dev.handle_read(addr, width)?);
+        }
+        match self.unmapped_policy {
+            UnmappedPolicy::Error => {
+                warn!("emu_handle: no emul handler for data abort ipa {:#x}", addr);
+                Err(DeviceError::UnmappedAddress(addr))
+            }
+            UnmappedPolicy::ReadZeroWriteIgnore => {
+                debug!("emu_handle: read zero from unmapped ipa {:#x}", addr);
+                width_mask(width).map(|_| 0)
+            }
+            UnmappedPolicy::ReadOnesWriteIgnore => {
+                debug!("emu_handle: read ones from unmapped ipa {:#x}", addr);
+                width_mask(width)
+            }
         }
-        panic!("emu_handle: no emul handler for data abort ipa {:#x}", addr);
     }
 
     /// Handle the MMIO write by GuestPhysAddr, data width and the value need to write, call specific device to write the value
-    pub fn handle_mmio_write(&self, addr: GuestPhysAddr, width: usize, val: usize) {
+    ///
+    /// # Errors
+    ///
+    /// Under the default [`UnmappedPolicy::Error`], an address that no emu
+    /// device handles returns [`AxError::BadAddress`] instead of panicking as
+    /// it used to, so callers must not drop the result. The ignore policies
+    /// return [`AxError::InvalidInput`] for an invalid access width.
+    ///
+    /// [`AxError::BadAddress`]: axerrno::AxError::BadAddress
+    /// [`AxError::InvalidInput`]: axerrno::AxError::InvalidInput
+    pub fn handle_mmio_write(&self, addr: GuestPhysAddr, width: usize, val: usize) -> AxResult {
+        self.handle_mmio_write_detailed(addr, width, val)
+            .map_err(Into::into)
+    }
+
+    /// Same as [`Self::handle_mmio_write`], but return a [`DeviceError`] telling why the write failed
+    pub fn handle_mmio_write_detailed(
+        &self,
+        addr: GuestPhysAddr,
+        width: usize,
+        val: usize,
+    ) -> DeviceResult {
         if let Some(emu_dev) = self.find_dev(addr) {
             info!(
                 "emu: {:?} handler write ipa {:#x}",
@@ -83,11 +273,308 @@ impl AxVmDevices {
                 addr
             );
             emu_dev.handle_write(addr, width, val);
-            return;
+            return Ok(());
+        }
+        match self.unmapped_policy {
+            UnmappedPolicy::Error => {
+                warn!(
+                    "emu_handler: no emul handler for data abort ipa {:#x}",
+                    addr
+                );
+                Err(DeviceError::UnmappedAddress(addr))
+            }
+            UnmappedPolicy::ReadZeroWriteIgnore | UnmappedPolicy::ReadOnesWriteIgnore => {
+                debug!("emu_handler: ignore write to unmapped ipa {:#x}", addr);
+                width_mask(width).map(|_| ())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use axerrno::AxError;
+
+    struct MockDevice {
+        emu_type: EmuDeviceType,
+        range: AddrRange<GuestPhysAddr>,
+    }
+
+    impl BaseDeviceOps for MockDevice {
+        fn emu_type(&self) -> EmuDeviceType {
+            self.emu_type
+        }
+
+        fn address_range(&self) -> AddrRange<GuestPhysAddr> {
+            self.range
+        }
+
+        fn handle_read(&self, _addr: GuestPhysAddr, _width: usize) -> AxResult<usize> {
+            Ok(0)
+        }
+
+        fn handle_write(&self, _addr: GuestPhysAddr, _width: usize, _val: usize) {}
+    }
+
+    fn mock_dev(emu_type: EmuDeviceType, start: usize, size: usize) -> Arc<dyn BaseDeviceOps> {
+        Arc::new(MockDevice {
+            emu_type,
+            range: AddrRange::from_start_size(GuestPhysAddr::from(start), size),
+        })
+    }
+
+    fn new_devices() -> AxVmDevices {
+        AxVmDevices::new(AxVmDeviceConfig::new(Vec::new()))
+    }
+
+    fn starts(devs: &[Arc<dyn BaseDeviceOps>]) -> Vec<usize> {
+        devs.iter()
+            .map(|dev| dev.address_range().start.as_usize())
+            .collect()
+    }
+
+    #[test]
+    fn test_find_devs_in_range() {
+        let mut devices = new_devices();
+        devices
+            .add_mmio_devs(vec![
+                mock_dev(EmuDeviceType::EmuDeviceTConsole, 0x1000, 0x1000),
+                mock_dev(EmuDeviceType::EmuDeviceTConsole, 0x2000, 0x1000),
+                mock_dev(EmuDeviceType::EmuDeviceTConsole, 0x8000, 0x1000),
+            ])
+            .unwrap();
+
+        let gpa = GuestPhysAddr::from;
+        // Overlapping both adjacent devices.
+        assert_eq!(
+            starts(&devices.find_devs_in_range(gpa(0x1800), gpa(0x2800))),
+            [0x1000, 0x2000]
+        );
+        // Ending exactly where the next device begins.
+        assert_eq!(
+            starts(&devices.find_devs_in_range(gpa(0x1000), gpa(0x2000))),
+            [0x1000]
+        );
+        // Outside every device.
+        assert!(
+            devices
+                .find_devs_in_range(gpa(0x4000), gpa(0x8000))
+                .is_empty()
+        );
+        // Empty span inside a device.
+        assert!(
+            devices
+                .find_devs_in_range(gpa(0x1800), gpa(0x1800))
+                .is_empty()
+        );
+        // Reversed span.
+        assert!(
+            devices
+                .find_devs_in_range(gpa(0x2800), gpa(0x1800))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_find_by_type() {
+        let mut devices = new_devices();
+        devices
+            .add_mmio_devs(vec![
+                mock_dev(EmuDeviceType::EmuDeviceTVirtioBlk, 0x1000, 0x1000),
+                mock_dev(EmuDeviceType::EmuDeviceTConsole, 0x2000, 0x1000),
+                mock_dev(EmuDeviceType::EmuDeviceTVirtioBlk, 0x3000, 0x1000),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            starts(&devices.find_by_type(EmuDeviceType::EmuDeviceTVirtioBlk)),
+            [0x1000, 0x3000]
+        );
+        assert_eq!(
+            starts(&devices.find_by_type(EmuDeviceType::EmuDeviceTConsole)),
+            [0x2000]
+        );
+        assert!(
+            devices
+                .find_by_type(EmuDeviceType::EmuDeviceTVirtioNet)
+                .is_empty()
+        );
+
+        let first = devices
+            .get_first_by_type(EmuDeviceType::EmuDeviceTVirtioBlk)
+            .unwrap();
+        assert_eq!(first.address_range().start.as_usize(), 0x1000);
+        assert!(
+            devices
+                .get_first_by_type(EmuDeviceType::EmuDeviceTVirtioNet)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_add_mmio_devs() {
+        let mut devices = new_devices();
+        devices
+            .add_mmio_dev(mock_dev(EmuDeviceType::EmuDeviceTConsole, 0x1000, 0x1000))
+            .unwrap();
+        devices
+            .add_mmio_devs(vec![
+                mock_dev(EmuDeviceType::EmuDeviceTConsole, 0x2000, 0x1000),
+                mock_dev(EmuDeviceType::EmuDeviceTConsole, 0x3000, 0x1000),
+            ])
+            .unwrap();
+        assert_eq!(devices.iter_devices().len(), 3);
+    }
+
+    #[test]
+    fn test_add_mmio_devs_conflict() {
+        let mut devices = new_devices();
+        devices
+            .add_mmio_dev(mock_dev(EmuDeviceType::EmuDeviceTConsole, 0x1000, 0x1000))
+            .unwrap();
+
+        // The third device overlaps with the already added one.
+        let res = devices.add_mmio_devs(vec![
+            mock_dev(EmuDeviceType::EmuDeviceTConsole, 0x2000, 0x1000),
+            mock_dev(EmuDeviceType::EmuDeviceTConsole, 0x3000, 0x1000),
+            mock_dev(EmuDeviceType::EmuDeviceTConsole, 0x1800, 0x1000),
+        ]);
+        assert_eq!(
+            res,
+            Err(DeviceError::AddressOverlap(AddrRange::from_start_size(
+                GuestPhysAddr::from(0x1800),
+                0x1000
+            )))
+        );
+        assert_eq!(devices.iter_devices().len(), 1);
+
+        // The third device overlaps with the first one of the same batch.
+        let res = devices.add_mmio_devs(vec![
+            mock_dev(EmuDeviceType::EmuDeviceTConsole, 0x2000, 0x1000),
+            mock_dev(EmuDeviceType::EmuDeviceTConsole, 0x3000, 0x1000),
+            mock_dev(EmuDeviceType::EmuDeviceTConsole, 0x2800, 0x1000),
+        ]);
+        assert_eq!(
+            res,
+            Err(DeviceError::AddressOverlap(AddrRange::from_start_size(
+                GuestPhysAddr::from(0x2800),
+                0x1000
+            )))
+        );
+        assert_eq!(devices.iter_devices().len(), 1);
+    }
+
+    #[test]
+    fn test_iter_devices() {
+        let devs = vec![
+            mock_dev(EmuDeviceType::EmuDeviceTVirtioBlk, 0x1000, 0x1000),
+            mock_dev(EmuDeviceType::EmuDeviceTConsole, 0x2000, 0x200),
+            mock_dev(EmuDeviceType::EmuDeviceTVirtioNet, 0x8000, 0x4000),
+        ];
+        let mut devices = new_devices();
+        devices.add_mmio_devs(devs.clone()).unwrap();
+
+        let descs = devices.iter_devices();
+        assert_eq!(descs.len(), devs.len());
+        for (desc, dev) in descs.iter().zip(&devs) {
+            assert_eq!(desc.emu_type, dev.emu_type());
+            assert_eq!(desc.address_range, dev.address_range());
         }
-        panic!(
-            "emu_handler: no emul handler for data abort ipa {:#x}",
-            addr
+    }
+
+    fn unmapped_devices(policy: UnmappedPolicy) -> AxVmDevices {
+        let mut devices = new_devices();
+        devices
+            .add_mmio_dev(mock_dev(EmuDeviceType::EmuDeviceTConsole, 0x1000, 0x1000))
+            .unwrap();
+        devices.set_unmapped_policy(policy);
+        devices
+    }
+
+    #[test]
+    fn test_unmapped_policy_error() {
+        let devices = unmapped_devices(UnmappedPolicy::Error);
+        let addr = GuestPhysAddr::from(0x8000);
+        assert_eq!(devices.handle_mmio_read(addr, 4), Err(AxError::BadAddress));
+        assert_eq!(
+            devices.handle_mmio_read_detailed(addr, 4),
+            Err(DeviceError::UnmappedAddress(addr))
+        );
+        assert_eq!(
+            devices.handle_mmio_write(addr, 4, 0),
+            Err(AxError::BadAddress)
+        );
+        assert_eq!(
+            devices.handle_mmio_write_detailed(addr, 4, 0),
+            Err(DeviceError::UnmappedAddress(addr))
+        );
+    }
+
+    #[test]
+    fn test_unmapped_policy_read_zero() {
+        let devices = unmapped_devices(UnmappedPolicy::ReadZeroWriteIgnore);
+        let addr = GuestPhysAddr::from(0x8000);
+        assert_eq!(devices.handle_mmio_read(addr, 4), Ok(0));
+        assert_eq!(devices.handle_mmio_write(addr, 4, 0x1234), Ok(()));
+    }
+
+    #[test]
+    fn test_unmapped_policy_read_ones() {
+        let devices = unmapped_devices(UnmappedPolicy::ReadOnesWriteIgnore);
+        let addr = GuestPhysAddr::from(0x8000);
+        assert_eq!(devices.handle_mmio_read(addr, 1), Ok(0xff));
+        assert_eq!(devices.handle_mmio_read(addr, 2), Ok(0xffff));
+        assert_eq!(devices.handle_mmio_read(addr, 4), Ok(0xffff_ffff));
+        assert_eq!(devices.handle_mmio_read(addr, 8), Ok(usize::MAX));
+        assert_eq!(devices.handle_mmio_write(addr, 4, 0x1234), Ok(()));
+    }
+
+    #[test]
+    fn test_unmapped_policy_invalid_width() {
+        let addr = GuestPhysAddr::from(0x8000);
+        for policy in [
+            UnmappedPolicy::ReadZeroWriteIgnore,
+            UnmappedPolicy::ReadOnesWriteIgnore,
+        ] {
+            let devices = unmapped_devices(policy);
+            for width in [0, 3, 16] {
+                assert_eq!(
+                    devices.handle_mmio_read_detailed(addr, width),
+                    Err(DeviceError::InvalidAccessWidth(width))
+                );
+                assert_eq!(
+                    devices.handle_mmio_read(addr, width),
+                    Err(AxError::InvalidInput)
+                );
+                assert_eq!(
+                    devices.handle_mmio_write_detailed(addr, width, 0),
+                    Err(DeviceError::InvalidAccessWidth(width))
+                );
+                assert_eq!(
+                    devices.handle_mmio_write(addr, width, 0),
+                    Err(AxError::InvalidInput)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_insert_mmio_devs_no_memory() {
+        let mut devices = new_devices();
+        devices
+            .add_mmio_dev(mock_dev(EmuDeviceType::EmuDeviceTConsole, 0x1000, 0x1000))
+            .unwrap();
+
+        // A `usize::MAX` reservation fails with a capacity overflow rather than
+        // a real allocation failure, standing in for OOM on the shared path of
+        // `add_mmio_dev` and `add_mmio_devs`.
+        let devs = [mock_dev(EmuDeviceType::EmuDeviceTConsole, 0x2000, 0x1000)];
+        assert_eq!(
+            devices.insert_mmio_devs(&devs, usize::MAX),
+            Err(DeviceError::NoMemory)
         );
+        assert_eq!(devices.iter_devices().len(), 1);
     }
 }
@@ -0,0 +1,76 @@
+use axaddrspace::GuestPhysAddr;
+use axerrno::AxError;
+use memory_addr::AddrRange;
+
+/// The error type for device operations of axdevice
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceError {
+    /// No emulated device is mapped at the accessed address
+    UnmappedAddress(GuestPhysAddr),
+    /// The access width is not supported
+    InvalidAccessWidth(usize),
+    /// The address range of a new device overlaps with an existing one
+    AddressOverlap(AddrRange<GuestPhysAddr>),
+    /// Not enough memory to register the device
+    NoMemory,
+    /// The emulated device itself failed the access
+    Device(AxError),
+}
+
+/// A specialized `Result` type with [`DeviceError`] as the error type
+pub type DeviceResult<T = ()> = Result<T, DeviceError>;
+
+impl From<AxError> for DeviceError {
+    fn from(err: AxError) -> Self {
+        Self::Device(err)
+    }
+}
+
+impl From<DeviceError> for AxError {
+    fn from(err: DeviceError) -> Self {
+        match err {
+            DeviceError::UnmappedAddress(_) => AxError::BadAddress,
+            DeviceError::InvalidAccessWidth(_) => AxError::InvalidInput,
+            DeviceError::AddressOverlap(_) => AxError::AlreadyExists,
+            DeviceError::NoMemory => AxError::NoMemory,
+            DeviceError::Device(err) => err,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_error_to_ax_error() {
+        let addr = GuestPhysAddr::from(0x1000);
+        let range = AddrRange::from_start_size(addr, 0x1000);
+        assert_eq!(
+            AxError::from(DeviceError::UnmappedAddress(addr)),
+            AxError::BadAddress
+        );
+        assert_eq!(
+            AxError::from(DeviceError::InvalidAccessWidth(3)),
+            AxError::InvalidInput
+        );
+        assert_eq!(
+            AxError::from(DeviceError::AddressOverlap(range)),
+            AxError::AlreadyExists
+        );
+        assert_eq!(AxError::from(DeviceError::NoMemory), AxError::NoMemory);
+        assert_eq!(
+            AxError::from(DeviceError::Device(AxError::Unsupported)),
+            AxError::Unsupported
+        );
+    }
+
+    #[test]
+    fn test_ax_error_round_trip() {
+        for err in [AxError::BadState, AxError::InvalidInput, AxError::Io] {
+            let dev_err = DeviceError::from(err);
+            assert_eq!(dev_err, DeviceError::Device(err));
+            assert_eq!(AxError::from(dev_err), err);
+        }
+    }
+}
@@ -6,7 +6,7 @@
 //!
 //! The `log` crate is included for logging purposes, with macros being imported globally.
 //!
-//! The module is structured into two main parts: `config` and `device`, which manage the configuration and handling of AxVm devices respectively.
+//! The module is structured into three main parts: `config`, `device` and `error`, which manage the configuration, the handling and the errors of AxVm devices respectively.
 
 extern crate alloc;
 #[macro_use]
@@ -14,6 +14,8 @@ extern crate log;
 
 mod config;
 mod device;
+mod error;
 
 pub use config::AxVmDeviceConfig;
-pub use device::AxVmDevices;
+pub use device::{AxVmDevices, DeviceDescriptor, UnmappedPolicy};
+pub use error::{DeviceError, DeviceResult};